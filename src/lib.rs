@@ -5,6 +5,8 @@ use std::fs;
 // Constants
 const PHPCS_CONFIG_FILES: &[&str] = &[".phpcs.xml", "phpcs.xml", ".phpcs.xml.dist", "phpcs.xml.dist"];
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEFAULT_DOWNLOAD_BASE_URL: &str = "https://github.com/GeneaLabs/zed-phpcs-lsp/releases/download";
+const PHPCS_RELEASES_URL: &str = "https://github.com/PHPCSStandards/PHP_CodeSniffer/releases/download";
 
 struct PhpcsLspExtension {
     phpcs_lsp: Option<PhpcsLspServer>,
@@ -51,13 +53,100 @@ impl PhpcsLspServer {
             return Ok(path);
         }
 
-        // Download the binary from GitHub
-        let downloaded_path = self.download_binary(&binary_name)?;
-        self.cached_binary_path = Some(downloaded_path.clone());
-        Ok(downloaded_path)
+        // Download the binary from GitHub (or the configured mirror), falling back to a
+        // previously downloaded version if the current release assets are not available yet
+        let user_settings = LspSettings::for_worktree(Self::LANGUAGE_SERVER_ID, worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.settings);
+        let download_base_url = PhpcsLspExtension::download_base_url(user_settings.as_ref());
+        match self.download_binary(&binary_name, &download_base_url) {
+            Ok(path) => {
+                Self::remove_stale_version_dirs(&binary_name);
+                self.cached_binary_path = Some(path.clone());
+                Ok(path)
+            }
+            Err(download_error) => {
+                let Some((fallback_version, fallback_path)) = Self::find_fallback_binary(&binary_name) else {
+                    return Err(download_error);
+                };
+                eprintln!(
+                    "WARNING: PHPCS LSP {} could not be downloaded ({}). Falling back to previously downloaded version {} at {}",
+                    VERSION, download_error, fallback_version, fallback_path
+                );
+                // Not cached, so the next server start retries the download
+                Ok(fallback_path)
+            }
+        }
+    }
+
+    fn find_fallback_binary(binary_name: &str) -> Option<(String, String)> {
+        Self::previous_version_dirs()
+            .into_iter()
+            .find(|dir_name| Self::has_binary(dir_name, binary_name))
+            .map(|dir_name| {
+                let version = dir_name.trim_start_matches("phpcs-").to_string();
+                let binary_path = format!("{}/{}", dir_name, binary_name);
+                (version, binary_path)
+            })
+    }
+
+    fn remove_stale_version_dirs(binary_name: &str) {
+        let previous_dirs = Self::previous_version_dirs();
+
+        // Keep the newest previous binary as a download fallback
+        let dir_to_keep = previous_dirs
+            .iter()
+            .find(|dir_name| Self::has_binary(dir_name, binary_name))
+            .cloned();
+
+        for dir_name in previous_dirs {
+            if dir_to_keep.as_ref() == Some(&dir_name) {
+                continue;
+            }
+
+            match fs::remove_dir_all(&dir_name) {
+                Ok(()) => eprintln!("Removed stale PHPCS LSP directory {}", dir_name),
+                Err(e) => eprintln!("Failed to remove stale PHPCS LSP directory {}: {}", dir_name, e),
+            }
+        }
+    }
+
+    // Returns the phpcs-* version directories other than the current one, newest first
+    fn previous_version_dirs() -> Vec<String> {
+        let current_dir = format!("phpcs-{}", VERSION);
+        let Ok(entries) = fs::read_dir(".") else {
+            return Vec::new();
+        };
+
+        let mut version_dirs: Vec<(Vec<u64>, String)> = entries
+            .flatten()
+            .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|dir_name| *dir_name != current_dir)
+            .filter_map(|dir_name| {
+                let parsed_version = Self::parse_version(dir_name.strip_prefix("phpcs-")?)?;
+                Some((parsed_version, dir_name))
+            })
+            .collect();
+
+        version_dirs.sort_by(|a, b| b.0.cmp(&a.0));
+        version_dirs.into_iter().map(|(_, dir_name)| dir_name).collect()
+    }
+
+    fn has_binary(dir_name: &str, binary_name: &str) -> bool {
+        fs::metadata(format!("{}/{}", dir_name, binary_name))
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+    }
+
+    fn parse_version(version: &str) -> Option<Vec<u64>> {
+        version
+            .split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect()
     }
     
-    fn download_binary(&self, binary_name: &str) -> Result<String> {
+    fn download_binary(&self, binary_name: &str, download_base_url: &str) -> Result<String> {
         // Use the same pattern as Gleam extension
         let version_dir = format!("phpcs-{}", VERSION);
         let binary_path = format!("{}/{}", version_dir, binary_name);
@@ -76,7 +165,8 @@ impl PhpcsLspServer {
         let archive_name = format!("{}.{}", binary_name, archive_ext);
         
         let release_url = format!(
-            "https://github.com/GeneaLabs/zed-phpcs-lsp/releases/download/{}/{}",
+            "{}/{}/{}",
+            download_base_url,
             VERSION,
             archive_name
         );
@@ -167,11 +257,12 @@ impl zed::Extension for PhpcsLspExtension {
             .ok()
             .and_then(|lsp_settings| lsp_settings.settings.clone());
         
-        // Download PHPCS PHAR to LSP server directory - LSP server will find it automatically
-        Self::download_phar_if_needed("phpcs.phar").ok();
-        
-        // Download PHPCBF PHAR to LSP server directory - LSP server will find it automatically  
-        Self::download_phar_if_needed("phpcbf.phar").ok();
+        // Check whether bundled PHAR downloads are enabled (default: true)
+        let download_bundled_tools = user_settings
+            .as_ref()
+            .and_then(|settings| settings.get("download_bundled_tools"))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(true);
         
         // Extract custom paths from user settings
         let mut phpcs_path_to_use: Option<String> = None;
@@ -197,12 +288,57 @@ impl zed::Extension for PhpcsLspExtension {
             }
         }
         
+        // Check for a pinned PHPCS version to download instead of the default bundled PHARs
+        let pinned_phpcs_version = user_settings
+            .as_ref()
+            .and_then(|settings| settings.get("phpcs_version"))
+            .and_then(|value| value.as_str())
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty());
+        
+        if download_bundled_tools {
+            let download_base_url = Self::download_base_url(user_settings.as_ref());
+            
+            // Download PHPCS PHAR to LSP server directory - LSP server will find it automatically
+            Self::download_phar_if_needed("phpcs.phar", &download_base_url).ok();
+            
+            // Download PHPCBF PHAR to LSP server directory - LSP server will find it automatically  
+            Self::download_phar_if_needed("phpcbf.phar", &download_base_url).ok();
+            
+            // Download versioned PHARs for the pinned version, unless custom paths were configured
+            if let Some(ref phpcs_version) = pinned_phpcs_version {
+                if phpcs_path_to_use.is_none() {
+                    phpcs_path_to_use = Self::download_pinned_phar("phpcs", phpcs_version);
+                }
+                if phpcbf_path_to_use.is_none() {
+                    phpcbf_path_to_use = Self::download_pinned_phar("phpcbf", phpcs_version);
+                }
+            }
+        } else if pinned_phpcs_version.is_some() {
+            eprintln!("phpcs_version is ignored because download_bundled_tools is disabled");
+        }
+        
         // Determine standard/config to use (priority order: config file -> settings -> env -> default)
         let mut standard_to_use: Option<String> = None;
         
-        // Try to find phpcs configuration file first (highest priority)
-        if let Some(config_file) = Self::find_phpcs_config(worktree) {
-            standard_to_use = Some(config_file);
+        // Use an explicitly configured ruleset file first (highest priority)
+        let config_file_to_use = user_settings
+            .as_ref()
+            .and_then(|settings| settings.get("config_file"))
+            .and_then(|value| value.as_str())
+            .map(|config_file| config_file.trim())
+            .filter(|config_file| !config_file.is_empty())
+            .and_then(|config_file| Self::resolve_config_file(worktree, config_file));
+        
+        if let Some(ref config_file) = config_file_to_use {
+            standard_to_use = Some(config_file.clone());
+        }
+        
+        // Otherwise try to discover a phpcs configuration file in the project
+        if standard_to_use.is_none() {
+            if let Some(config_file) = Self::find_phpcs_config(worktree) {
+                standard_to_use = Some(config_file);
+            }
         }
         
         // Check for user-configured coding standard from settings.json
@@ -251,6 +387,11 @@ impl zed::Extension for PhpcsLspExtension {
             options.insert("standard".to_string(), zed::serde_json::Value::String(standard.clone()));
         }
         
+        // Pass the resolved ruleset file to the LSP server as well
+        if let Some(config_file) = config_file_to_use {
+            options.insert("config_file".to_string(), zed::serde_json::Value::String(config_file));
+        }
+        
         // Pass custom PHPCS path to the LSP server if configured
         if let Some(phpcs_path) = phpcs_path_to_use {
             options.insert("phpcs_path".to_string(), zed::serde_json::Value::String(phpcs_path));
@@ -261,6 +402,11 @@ impl zed::Extension for PhpcsLspExtension {
             options.insert("phpcbf_path".to_string(), zed::serde_json::Value::String(phpcbf_path));
         }
         
+        // Tell the LSP server not to look for bundled PHARs that were never downloaded
+        if !download_bundled_tools {
+            options.insert("download_bundled_tools".to_string(), zed::serde_json::Value::Bool(false));
+        }
+        
         if options.is_empty() {
             Ok(None)
         } else {
@@ -272,7 +418,7 @@ impl zed::Extension for PhpcsLspExtension {
 
 impl PhpcsLspExtension {
     
-    fn download_phar_if_needed(phar_name: &str) -> Result<String> {
+    fn download_phar_if_needed(phar_name: &str, download_base_url: &str) -> Result<String> {
         // Use the same pattern as Gleam extension for consistency
         let version_dir = format!("phpcs-{}", VERSION);
         let phar_path = format!("{}/{}", version_dir, phar_name);
@@ -286,7 +432,8 @@ impl PhpcsLspExtension {
         let archive_name = format!("{}.tar.gz", phar_name);
         
         let release_url = format!(
-            "https://github.com/GeneaLabs/zed-phpcs-lsp/releases/download/{}/{}",
+            "{}/{}/{}",
+            download_base_url,
             VERSION,
             archive_name
         );
@@ -316,6 +463,72 @@ impl PhpcsLspExtension {
     }
 
     
+    fn download_pinned_phar(tool: &str, phpcs_version: &str) -> Option<String> {
+        // Only allow plain version numbers since the version becomes part of the file name
+        if PhpcsLspServer::parse_version(phpcs_version).is_none() {
+            eprintln!("Invalid phpcs_version '{}', using the default bundled {}", phpcs_version, tool);
+            return None;
+        }
+        
+        // Store versioned PHARs next to the default ones so different pins can coexist
+        let version_dir = format!("phpcs-{}", VERSION);
+        let phar_path = format!("{}/{}-{}.phar", version_dir, tool, phpcs_version);
+        
+        if fs::metadata(&phar_path).is_err() {
+            // Pinned versions come straight from the upstream PHP_CodeSniffer releases
+            let release_url = format!("{}/{}/{}.phar", PHPCS_RELEASES_URL, phpcs_version, tool);
+            
+            let download_result = fs::create_dir_all(&version_dir)
+                .map_err(|e| e.to_string())
+                .and_then(|_| zed::download_file(&release_url, &phar_path, zed::DownloadedFileType::Uncompressed));
+            if let Err(e) = download_result {
+                eprintln!(
+                    "Failed to download {} {} from {}: {}. Using the default bundled {}",
+                    tool, phpcs_version, release_url, e, tool
+                );
+                return None;
+            }
+            
+            // Make the PHAR executable on Unix-like systems
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                if let Ok(metadata) = fs::metadata(&phar_path) {
+                    let mut perms = metadata.permissions();
+                    perms.set_mode(0o755);
+                    fs::set_permissions(&phar_path, perms).ok();
+                }
+            }
+        }
+        
+        // The LSP server runs from the worktree, so it needs an absolute path
+        let absolute_path = env::current_dir().ok()?.join(phar_path);
+        absolute_path.to_str().map(|path_str| path_str.to_string())
+    }
+    
+    fn resolve_config_file(worktree: &zed::Worktree, config_file: &str) -> Option<String> {
+        let config_path = std::path::PathBuf::from(config_file);
+        
+        // Absolute paths are outside the extension sandbox, so the LSP server validates them
+        if config_path.is_absolute() {
+            return Some(config_file.to_string());
+        }
+        
+        // Relative paths are resolved against the workspace root via the worktree API
+        if worktree.read_text_file(config_file).is_err() {
+            eprintln!(
+                "Configured PHPCS config file {} not found in workspace, falling back to auto-discovery",
+                config_file
+            );
+            return None;
+        }
+        
+        std::path::PathBuf::from(worktree.root_path())
+            .join(config_path)
+            .to_str()
+            .map(|path_str| path_str.to_string())
+    }
+    
     fn find_phpcs_config(worktree: &zed::Worktree) -> Option<String> {
         let root_path = std::path::PathBuf::from(worktree.root_path());
         
@@ -331,8 +544,85 @@ impl PhpcsLspExtension {
         
         None
     }
+    
+    // Release download base URL, overridable via the download_base_url setting for mirrors
+    fn download_base_url(user_settings: Option<&zed::serde_json::Value>) -> String {
+        let Some(configured_url) = user_settings
+            .and_then(|settings| settings.get("download_base_url"))
+            .and_then(|value| value.as_str())
+            .map(|url| url.trim().trim_end_matches('/'))
+            .filter(|url| !url.is_empty())
+        else {
+            return DEFAULT_DOWNLOAD_BASE_URL.to_string();
+        };
+        
+        // Downloaded binaries are executed, so only HTTPS mirrors are accepted
+        let host = configured_url
+            .strip_prefix("https://")
+            .and_then(|rest| rest.split('/').next())
+            .and_then(|authority| authority.split(':').next())
+            .unwrap_or("");
+        
+        let is_invalid = host.is_empty()
+            || configured_url.contains(['?', '#'])
+            || configured_url.chars().any(char::is_whitespace);
+        if is_invalid {
+            eprintln!(
+                "Invalid download_base_url '{}', using default {}",
+                configured_url, DEFAULT_DOWNLOAD_BASE_URL
+            );
+            return DEFAULT_DOWNLOAD_BASE_URL.to_string();
+        }
+        
+        configured_url.to_string()
+    }
 }
 
 zed::register_extension!(PhpcsLspExtension);
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_url_for(configured_url: &str) -> String {
+        let settings = zed::serde_json::json!({ "download_base_url": configured_url });
+        PhpcsLspExtension::download_base_url(Some(&settings))
+    }
+
+    #[test]
+    fn download_base_url_defaults_when_unset() {
+        assert_eq!(PhpcsLspExtension::download_base_url(None), DEFAULT_DOWNLOAD_BASE_URL);
+        assert_eq!(base_url_for("  "), DEFAULT_DOWNLOAD_BASE_URL);
+    }
+
+    #[test]
+    fn download_base_url_accepts_https_mirrors() {
+        assert_eq!(base_url_for("https://mirror.example.com/releases/"), "https://mirror.example.com/releases");
+        assert_eq!(base_url_for("https://mirror.example.com:8443/releases"), "https://mirror.example.com:8443/releases");
+    }
 
+    #[test]
+    fn download_base_url_rejects_invalid_urls() {
+        for invalid_url in [
+            "http://mirror.example.com/releases",
+            "ftp://mirror.example.com/releases",
+            "https://",
+            "https://:8080/releases",
+            "https://mirror.example.com/releases?token=abc",
+            "https://mirror.example.com/releases#latest",
+            "https://mirror.example.com/my releases",
+        ] {
+            assert_eq!(base_url_for(invalid_url), DEFAULT_DOWNLOAD_BASE_URL, "{}", invalid_url);
+        }
+    }
+
+    #[test]
+    fn parse_version_orders_numerically() {
+        assert!(PhpcsLspServer::parse_version("0.10.0") > PhpcsLspServer::parse_version("0.9.1"));
+        assert!(PhpcsLspServer::parse_version("1.0.0") > PhpcsLspServer::parse_version("0.99.99"));
+        assert_eq!(PhpcsLspServer::parse_version("0.4.1"), Some(vec![0, 4, 1]));
+        assert_eq!(PhpcsLspServer::parse_version("0.4.x"), None);
+        assert_eq!(PhpcsLspServer::parse_version("3."), None);
+        assert_eq!(PhpcsLspServer::parse_version("3..7"), None);
+    }
+}